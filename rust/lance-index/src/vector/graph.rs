@@ -24,16 +24,38 @@ pub trait VectorStorage<T: Float> {
 
     /// Returns the number of vectors in the storage.
     fn len(&self) -> usize;
+
+    /// Opaque, per-query state built once by [`Self::prepare_query`] and then
+    /// reused by every [`Self::distance`] call a single query makes.
+    ///
+    /// For [`InMemoryVectorStorage`] this is just the query vector itself.
+    /// Quantized backends (e.g. product quantization) use it to carry a
+    /// precomputed distance table, so that table is built exactly once per
+    /// query instead of once per candidate.
+    type QueryContext;
+
+    /// Precompute whatever [`Self::distance`] needs for `query`.
+    fn prepare_query(&self, query: &[T]) -> Self::QueryContext;
+
+    /// Distance from the query captured in `ctx` to the `idx`-th vector in
+    /// this storage.
+    ///
+    /// Implementations that only hold a quantized representation of their
+    /// vectors compute this without ever materializing the original vector,
+    /// so callers should go through this method instead of combining
+    /// [`Self::get`] with their own distance function.
+    fn distance(&self, ctx: &Self::QueryContext, idx: u32) -> f32;
 }
 
 /// A VectorStore backed by in-memory matrix.
 pub struct InMemoryVectorStorage<T: ArrowFloatType> {
     data: MatrixView<T>,
+    dist_fn: fn(&[T::Native], &[T::Native]) -> f32,
 }
 
 impl<T: ArrowFloatType> InMemoryVectorStorage<T> {
-    pub fn new(data: MatrixView<T>) -> Self {
-        Self { data }
+    pub fn new(data: MatrixView<T>, dist_fn: fn(&[T::Native], &[T::Native]) -> f32) -> Self {
+        Self { data, dist_fn }
     }
 }
 
@@ -45,4 +67,14 @@ impl<T: ArrowFloatType> VectorStorage<T::Native> for InMemoryVectorStorage<T> {
     fn len(&self) -> usize {
         self.data.num_rows()
     }
+
+    type QueryContext = Vec<T::Native>;
+
+    fn prepare_query(&self, query: &[T::Native]) -> Self::QueryContext {
+        query.to_vec()
+    }
+
+    fn distance(&self, ctx: &Self::QueryContext, idx: u32) -> f32 {
+        (self.dist_fn)(ctx, self.get(idx).expect("vector not found"))
+    }
 }
@@ -23,7 +23,17 @@ use num_traits::Float;
 use super::graph::{InMemoryVectorStorage, VectorStorage};
 
 mod builder;
+mod id_selector;
+mod persist;
+mod stats;
 mod storage;
+#[cfg(test)]
+mod test_utils;
+
+pub use id_selector::{BitmapSelector, IdSelector, RangeSelector};
+pub use persist::{DistanceMetric, HnswCheckpointWriter};
+pub use stats::{HnswStats, LayerStats};
+pub use storage::{DiskVectorStorage, PQDistanceTable, PQVectorStorage};
 
 #[derive(Debug, Eq)]
 pub struct GraphNode {
@@ -38,23 +48,20 @@ impl PartialEq for GraphNode {
 }
 
 impl GraphNode {
-    pub fn new(id: u32, neighbors: Vec<u32>) -> Self {
+    /// Create a new node at the given `level`, with an empty neighbor list
+    /// for each layer from `0` to `level` (inclusive).
+    pub fn new(id: u32, level: u16) -> Self {
         Self {
             id,
-            neighbors: vec![],
+            neighbors: vec![Vec::new(); level as usize + 1],
         }
     }
-}
 
-/// HNSW Graph
-///
-/// A sealed graph.
-pub struct HNSW<T: Float, S: VectorStorage<T>> {
-    vectors: S,
-
-    nodes: Vec<GraphNode>,
-
-    dist_fn: fn(&[T], &[T]) -> f32,
+    /// Reconstruct a node from its already-computed per-layer neighbor lists,
+    /// e.g. when restoring a checkpoint.
+    pub(crate) fn from_neighbors(id: u32, neighbors: Vec<Vec<u32>>) -> Self {
+        Self { id, neighbors }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -67,13 +74,21 @@ impl Eq for NodeWithDist<'_> {}
 
 impl PartialOrd for NodeWithDist<'_> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.distance.partial_cmp(&other.distance)
+        Some(self.cmp(other))
     }
 }
 
 impl Ord for NodeWithDist<'_> {
+    /// Orders primarily by `distance`, breaking ties on `node.id` so that
+    /// two distinct nodes never compare equal -- otherwise a `BTreeSet`
+    /// silently drops one of them on insert, which is common rather than
+    /// exceptional when distances come from a quantized lookup table (e.g.
+    /// [`PQVectorStorage`](storage::PQVectorStorage)).
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.distance.partial_cmp(&other.distance).unwrap()
+        self.distance
+            .partial_cmp(&other.distance)
+            .unwrap()
+            .then_with(|| self.node.id.cmp(&other.node.id))
     }
 }
 
@@ -83,7 +98,98 @@ impl<'a> NodeWithDist<'a> {
     }
 }
 
+/// HNSW Graph
+///
+/// A sealed graph.
+pub struct HNSW<T: Float, S: VectorStorage<T>> {
+    vectors: S,
+
+    nodes: Vec<GraphNode>,
+
+    /// Id of the node at the top layer, i.e. the entry point of the graph.
+    entry_point: Option<u32>,
+
+    /// The highest layer that currently has a node on it.
+    max_level: u16,
+
+    /// Ids of nodes whose adjacency has changed since the last
+    /// [`HnswCheckpointWriter::checkpoint`] call -- new nodes, plus any
+    /// earlier node that picked up a back-edge from one. Cleared by
+    /// `checkpoint` once those nodes have been flushed.
+    dirty: HashSet<u32>,
+}
+
 impl<T: Float, S: VectorStorage<T>> HNSW<T, S> {
+    /// Create an empty graph over `vectors`, ready to be populated by a
+    /// builder.
+    fn empty(vectors: S) -> Self {
+        Self::from_parts(vectors, vec![], None, 0)
+    }
+
+    /// Assemble a sealed graph directly from its parts: already-computed
+    /// adjacency lists, entry point, and `max_level`.
+    ///
+    /// Useful to rebuild a graph over a different [`VectorStorage`] backend
+    /// than the one it was built with -- e.g. swapping in a
+    /// [`PQVectorStorage`] for serving after building the adjacency against
+    /// full-precision vectors with [`builder::HNSWBUilder`]. See
+    /// [`Self::into_parts`] for the inverse.
+    pub fn from_parts(
+        vectors: S,
+        nodes: Vec<GraphNode>,
+        entry_point: Option<u32>,
+        max_level: u16,
+    ) -> Self {
+        Self {
+            vectors,
+            nodes,
+            entry_point,
+            max_level,
+            dirty: HashSet::new(),
+        }
+    }
+
+    /// Decompose a sealed graph into its adjacency lists, entry point, and
+    /// `max_level`, discarding its vector storage. See [`Self::from_parts`].
+    pub fn into_parts(self) -> (Vec<GraphNode>, Option<u32>, u16) {
+        (self.nodes, self.entry_point, self.max_level)
+    }
+
+    /// Restore a graph checkpointed with [`Self::to_writer`] (or
+    /// [`HnswCheckpointWriter`]) over `vectors`.
+    ///
+    /// `vectors` need not be the same storage backend the graph was built
+    /// with: pass a [`DiskVectorStorage`] to serve queries without loading
+    /// every vector into memory, or a freshly populated, larger
+    /// [`InMemoryVectorStorage`] to hand to [`builder::HNSWBUilder::resume`]
+    /// and keep building without recomputing existing neighbor lists.
+    pub fn from_reader<R: std::io::Read>(reader: R, vectors: S) -> std::io::Result<Self> {
+        let checkpoint = persist::from_reader(reader)?;
+        Ok(Self::from_parts(
+            vectors,
+            checkpoint.nodes,
+            checkpoint.entry_point,
+            checkpoint.max_level,
+        ))
+    }
+
+    /// Write the whole graph (adjacency lists, entry point, `max_level`, and
+    /// `metric`) to `writer` as a single self-contained checkpoint, covering
+    /// every node regardless of [`Self`]'s dirty-tracking state.
+    ///
+    /// This is independent of [`HnswCheckpointWriter`]'s incremental,
+    /// dirty-only flushing: unlike calling `HnswCheckpointWriter::checkpoint`
+    /// directly, `to_writer` never has "nothing to flush" -- it does not
+    /// consult or clear `dirty`, so it is safe to call repeatedly, or after
+    /// a `HnswCheckpointWriter` has already flushed everything.
+    pub fn to_writer<W: std::io::Write>(
+        &self,
+        writer: &mut W,
+        metric: DistanceMetric,
+    ) -> std::io::Result<()> {
+        persist::write_full(self, writer, metric)
+    }
+
     /// Neightbors of a node at a given level.
     fn neighbors(&self, id: u32, level: u16) -> Option<&[u32]> {
         self.nodes
@@ -95,22 +201,145 @@ impl<T: Float, S: VectorStorage<T>> HNSW<T, S> {
         self.nodes.get(id as usize)
     }
 
-    fn distance_to(&self, vector: &[T], idx: u32) -> f32 {
-        (self.dist_fn)(vector, self.vectors.get(idx).unwrap())
+    fn distance_to(&self, ctx: &S::QueryContext, idx: u32) -> f32 {
+        self.vectors.distance(ctx, idx)
     }
 
-    /// Search one level of the HNSW graph.
+    /// Add a directed edge from `from` to `to` at `level`.
+    ///
+    /// No-op if `from` does not have a layer at `level` (i.e. its assigned
+    /// level is lower than `level`).
+    fn connect(&mut self, from: u32, to: u32, level: u16) {
+        let Some(node) = self.nodes.get_mut(from as usize) else {
+            return;
+        };
+        let Some(layer) = node.neighbors.get_mut(level as usize) else {
+            return;
+        };
+        layer.push(to);
+        self.dirty.insert(from);
+    }
+
+    /// Prune the neighbor list of `id` at `level` down to the `m_max` closest
+    /// neighbors, dropping the farthest ones first.
+    fn prune_neighbors(&mut self, id: u32, level: u16, m_max: usize) {
+        let Some(node) = self.nodes.get(id as usize) else {
+            return;
+        };
+        let Some(layer) = node.neighbors.get(level as usize) else {
+            return;
+        };
+        if layer.len() <= m_max {
+            return;
+        }
+
+        let query = self.vectors.get(id).expect("vector not found").to_vec();
+        let ctx = self.vectors.prepare_query(&query);
+        let mut candidates: Vec<(u32, f32)> = layer
+            .iter()
+            .map(|&neighbor_id| (neighbor_id, self.distance_to(&ctx, neighbor_id)))
+            .collect();
+        candidates.sort_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+        candidates.truncate(m_max);
+
+        self.nodes[id as usize].neighbors[level as usize] =
+            candidates.into_iter().map(|(neighbor_id, _)| neighbor_id).collect();
+        self.dirty.insert(id);
+    }
+
+    /// Search the graph for the `k` nearest neighbors of `query`.
     ///
     /// Parameters
     /// ----------
     /// query : &[T]
     ///     Query vector
-    /// ep: &HNSWVector
+    /// k: usize
+    ///     The number of nearest neighbors to return.
+    /// ef: usize
+    ///     The size of the dynamic candidate list used while searching layer 0.
+    ///     Must be at least `k` to return `k` results.
+    ///
+    /// Returns
+    /// -------
+    /// Up to `k` `(id, distance)` pairs, sorted by ascending distance to `query`.
+    ///
+    pub fn search(&self, query: &[T], k: usize, ef: usize) -> Vec<(u32, f32)> {
+        let Some(mut ep) = self.entry_point else {
+            return vec![];
+        };
+        let ctx = self.vectors.prepare_query(query);
+
+        // Descend from the top layer to layer 1, keeping only the closest
+        // node found as the entry point for the layer below.
+        for layer in (1..=self.max_level).rev() {
+            let ep_node = self.node(ep).expect("entry point not found");
+            let nearest = self.search_layer(&ctx, ep_node, 1, layer, None);
+            if let Some(closest) = nearest.into_iter().next() {
+                ep = closest.node.id;
+            }
+        }
+
+        let ep_node = self.node(ep).expect("entry point not found");
+        self.search_layer(&ctx, ep_node, ef, 0, None)
+            .into_iter()
+            .take(k)
+            .map(|n| (n.node.id, n.distance))
+            .collect()
+    }
+
+    /// Search the graph for the `k` nearest neighbors of `query` among the
+    /// ids accepted by `selector`.
+    ///
+    /// The full graph is still traversed, including through nodes rejected by
+    /// `selector`, so that members reachable only via non-members remain
+    /// reachable; `selector` only decides which nodes make it into the
+    /// returned result set.
+    pub fn search_filtered(
+        &self,
+        query: &[T],
+        k: usize,
+        ef: usize,
+        selector: &dyn IdSelector,
+    ) -> Vec<(u32, f32)> {
+        let Some(mut ep) = self.entry_point else {
+            return vec![];
+        };
+        let ctx = self.vectors.prepare_query(query);
+
+        for layer in (1..=self.max_level).rev() {
+            let ep_node = self.node(ep).expect("entry point not found");
+            let nearest = self.search_layer(&ctx, ep_node, 1, layer, None);
+            if let Some(closest) = nearest.into_iter().next() {
+                ep = closest.node.id;
+            }
+        }
+
+        let ep_node = self.node(ep).expect("entry point not found");
+        self.search_layer(&ctx, ep_node, ef, 0, Some(selector))
+            .into_iter()
+            .take(k)
+            .map(|n| (n.node.id, n.distance))
+            .collect()
+    }
+
+    /// Search one level of the HNSW graph.
+    ///
+    /// Parameters
+    /// ----------
+    /// ctx : &S::QueryContext
+    ///     Query context, built once per query by [`VectorStorage::prepare_query`].
+    /// ep: &GraphNode
     ///     Enter point of the search
     /// ef: usize
     ///     The number of neighbors to return.
     /// layer: u16
     ///     The layer to search.
+    /// selector: Option<&dyn IdSelector>
+    ///     When `Some`, a node is only admitted into the returned result set
+    ///     once `selector.is_member(id)` holds; it is still expanded either
+    ///     way, so the search can tunnel through non-members to reach
+    ///     members on the other side. `None` admits every visited node,
+    ///     i.e. the behavior `search` wants.
     ///
     /// Returns
     /// -------
@@ -118,30 +347,38 @@ impl<T: Float, S: VectorStorage<T>> HNSW<T, S> {
     ///
     fn search_layer<'a>(
         &self,
-        query: &[T],
+        ctx: &S::QueryContext,
         ep: &'a GraphNode,
         ef: usize,
         layer: u16,
-    ) -> BTreeSet<NodeWithDist> {
+        selector: Option<&dyn IdSelector>,
+    ) -> BTreeSet<NodeWithDist<'a>> {
+        let is_member = |id: u32| selector.map_or(true, |s| s.is_member(id));
+
         let mut visited = HashSet::new();
         let mut candidates = BTreeSet::<NodeWithDist>::new();
         let mut results = BTreeSet::<NodeWithDist>::new();
         visited.insert(ep.id);
 
-        let d = self.distance_to(query, ep.id);
-        candidates.insert(NodeWithDist::new(ep, d));
+        let d = self.distance_to(ctx, ep.id);
+        let ep_with_dist = NodeWithDist::new(ep, d);
+        candidates.insert(ep_with_dist);
+        // Seed the result set with the entry point itself (if it's a
+        // member). When it isn't, `results` starts empty, which the
+        // `results.last()` checks below tolerate.
+        if is_member(ep.id) {
+            results.insert(ep_with_dist);
+        }
 
         while !candidates.is_empty() {
             let c = candidates.pop_first().unwrap();
-            let furthest = results
-                .last()
-                .map(|n| n.distance)
-                .expect("Result set is empty");
             visited.insert(c.node.id);
 
-            if c.distance > furthest {
-                // All elements in result set are evaluated
-                break;
+            if let Some(furthest) = results.last().map(|n| n.distance) {
+                if results.len() >= ef && c.distance > furthest {
+                    // All elements in result set are evaluated
+                    break;
+                }
             }
             // Unvisited neighbors
             let neighbors = self.neighbors(c.node.id, layer).unwrap();
@@ -151,21 +388,19 @@ impl<T: Float, S: VectorStorage<T>> HNSW<T, S> {
                     continue;
                 }
                 visited.insert(*n);
-                let distance = self.distance_to(query, *n);
+                let distance = self.distance_to(ctx, *n);
+                let furthest = results.last().map(|r| r.distance);
 
-                let furthest = results
-                    .last()
-                    .map(|n| n.distance)
-                    .expect("Result set is empty");
-
-                if distance < furthest {
+                if furthest.map_or(true, |f| distance < f) || results.len() < ef {
                     let new_node =
                         NodeWithDist::new(self.node(*n).expect("Node not found"), distance);
-
-                    results.insert(new_node);
                     candidates.insert(new_node);
-                    if results.len() > ef {
-                        results.pop_last();
+
+                    if is_member(*n) {
+                        results.insert(new_node);
+                        if results.len() > ef {
+                            results.pop_last();
+                        }
                     }
                 }
             }
@@ -175,4 +410,151 @@ impl<T: Float, S: VectorStorage<T>> HNSW<T, S> {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use arrow_array::types::Float32Type;
+
+    use super::builder::HNSWBUilder;
+    use super::storage::{DiskVectorStorage, PQVectorStorage, PQ4_NUM_CENTROIDS};
+    use super::test_utils::{default_test_hnsw, test_vectors, DEFAULT_DIM, DEFAULT_NUM_ROWS};
+    use super::{DistanceMetric, GraphNode, NodeWithDist, RangeSelector, HNSW};
+
+    #[test]
+    fn test_node_with_dist_breaks_ties_on_node_id() {
+        let a = GraphNode::new(0, 0);
+        let b = GraphNode::new(1, 0);
+
+        // Equal distances must not make distinct nodes compare `Equal`,
+        // or a `BTreeSet` silently drops one of them on insert.
+        let node_a = NodeWithDist::new(&a, 1.0);
+        let node_b = NodeWithDist::new(&b, 1.0);
+        assert_ne!(node_a.cmp(&node_b), std::cmp::Ordering::Equal);
+
+        let mut set = std::collections::BTreeSet::new();
+        set.insert(node_a);
+        set.insert(node_b);
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_search() {
+        let hnsw = default_test_hnsw();
+
+        let query = vec![0.0_f32; DEFAULT_DIM];
+        let results = hnsw.search(&query, 10, 50);
+
+        assert_eq!(results.len(), 10);
+        // Results must be sorted by ascending distance.
+        for pair in results.windows(2) {
+            assert!(pair[0].1 <= pair[1].1);
+        }
+        // Row 0 (`[0, 1, ..., 7]`) has the smallest values, so it must be the
+        // closest match to the all-zeros query.
+        assert_eq!(results[0].0, 0);
+    }
+
+    #[test]
+    fn test_search_filtered() {
+        let hnsw = default_test_hnsw();
+
+        // Row 0, the closest match to the all-zeros query, is excluded.
+        let selector = RangeSelector::new(1..DEFAULT_NUM_ROWS as u32);
+        let query = vec![0.0_f32; DEFAULT_DIM];
+        let results = hnsw.search_filtered(&query, 10, 50, &selector);
+
+        assert_eq!(results.len(), 10);
+        assert!(results.iter().all(|(id, _)| *id != 0));
+    }
+
+    #[test]
+    fn test_search_over_pq_storage() {
+        // 4 rows, 4 dims, split into 2 subquantizers of 2 dims each.
+        const DIM: usize = 4;
+        const NUM_ROWS: usize = 4;
+        const NUM_SUB_VECTORS: usize = 2;
+        const SUB_VECTOR_DIM: usize = 2;
+
+        let raw: Vec<f32> = (0..(DIM * NUM_ROWS) as u32).map(|v| v as f32).collect();
+
+        // Build the adjacency over full-precision vectors first.
+        let hnsw = HNSWBUilder::new()
+            .max_level(4)
+            .max_num_edges(4)
+            .ef_construction(10)
+            .build(test_vectors(DIM, NUM_ROWS));
+        let (nodes, entry_point, max_level) = hnsw.into_parts();
+
+        // Encode each row's own sub-vectors as its own centroid (code == row
+        // id), so PQ distances match the original L2 distances exactly.
+        let mut codebook = vec![0f32; NUM_SUB_VECTORS * PQ4_NUM_CENTROIDS * SUB_VECTOR_DIM];
+        for row in 0..NUM_ROWS {
+            for sub in 0..NUM_SUB_VECTORS {
+                let centroid_start = (sub * PQ4_NUM_CENTROIDS + row) * SUB_VECTOR_DIM;
+                let value_start = row * DIM + sub * SUB_VECTOR_DIM;
+                codebook[centroid_start..centroid_start + SUB_VECTOR_DIM]
+                    .copy_from_slice(&raw[value_start..value_start + SUB_VECTOR_DIM]);
+            }
+        }
+        let codes: Vec<u8> = (0..NUM_ROWS)
+            .flat_map(|row| vec![row as u8; NUM_SUB_VECTORS])
+            .collect();
+        let pq_storage =
+            PQVectorStorage::<Float32Type>::new(NUM_SUB_VECTORS, SUB_VECTOR_DIM, codebook, codes);
+
+        // Swap the full-precision storage for the PQ one, reusing the same
+        // adjacency -- demonstrating PQVectorStorage plugged into a live
+        // HNSW, not just exercised by its own unit test.
+        let pq_hnsw = HNSW::from_parts(pq_storage, nodes, entry_point, max_level);
+
+        let query = raw[0..DIM].to_vec();
+        let results = pq_hnsw.search(&query, 1, 10);
+        assert_eq!(results[0].0, 0);
+        assert_eq!(results[0].1, 0.0);
+    }
+
+    #[test]
+    fn test_from_reader_disk_storage_round_trip() {
+        use std::io::Write;
+
+        const DIM: usize = 4;
+        const NUM_ROWS: usize = 20;
+
+        let hnsw = HNSWBUilder::new()
+            .max_level(4)
+            .max_num_edges(4)
+            .ef_construction(10)
+            .build(test_vectors(DIM, NUM_ROWS));
+
+        let mut buf = Vec::new();
+        hnsw.to_writer(&mut buf, DistanceMetric::L2).unwrap();
+
+        // Back the reloaded graph with a flat f32 file on disk, exercising
+        // the actual public `from_reader` + `DiskVectorStorage` path (rather
+        // than just `persist::from_reader`, which doesn't touch storage at
+        // all).
+        let path = std::env::temp_dir().join(format!(
+            "lance_hnsw_round_trip_test_{}_{}.vec",
+            std::process::id(),
+            DIM * NUM_ROWS
+        ));
+        {
+            let mut file = std::fs::File::create(&path).unwrap();
+            for v in (0..(DIM * NUM_ROWS) as u32).map(|v| v as f32) {
+                file.write_all(&v.to_le_bytes()).unwrap();
+            }
+        }
+        let disk_storage = DiskVectorStorage::open(&path, DIM, NUM_ROWS, DistanceMetric::L2.func());
+
+        let reloaded: HNSW<f32, DiskVectorStorage> =
+            HNSW::from_reader(buf.as_slice(), disk_storage).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let query = vec![0.0_f32; DIM];
+        let results = reloaded.search(&query, 5, 50);
+
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].0, 0);
+        for pair in results.windows(2) {
+            assert!(pair[0].1 <= pair[1].1);
+        }
+    }
+}
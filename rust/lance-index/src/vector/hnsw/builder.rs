@@ -13,15 +13,11 @@
 // limitations under the License.
 
 use arrow_array::types::Float32Type;
+use rand::Rng;
 
-use crate::vector::graph::InMemoryVectorStorage;
+use crate::vector::graph::{InMemoryVectorStorage, VectorStorage};
 
-use super::HNSW;
-
-struct HnswBuilderNode {
-    id: u32,
-    neighbors: Vec<u32>,
-}
+use super::{GraphNode, HNSW};
 
 /// HNSW Builder
 pub struct HNSWBUilder {
@@ -63,25 +59,111 @@ impl HNSWBUilder {
         self
     }
 
-    /// Build a HNSW graph.
-    pub fn build() -> HNSW<f32, InMemoryVectorStorage<Float32Type>> {
-        unimplemented!()
+    /// Build a HNSW graph over `vectors`.
+    pub fn build(
+        &self,
+        vectors: InMemoryVectorStorage<Float32Type>,
+    ) -> HNSW<f32, InMemoryVectorStorage<Float32Type>> {
+        let mut hnsw = HNSW::empty(vectors);
+        self.resume(&mut hnsw);
+        hnsw
     }
 
-    /// Assign random level to a new node
+    /// Continue building `hnsw`, inserting every vector of `hnsw.vectors`
+    /// that isn't in the graph yet, i.e. ids `hnsw.nodes.len()..hnsw.vectors.len()`,
+    /// without recomputing the neighbor lists of nodes already present.
+    ///
+    /// Use this to resume a graph restored from a checkpoint via
+    /// [`HNSW::from_reader`], after appending the vectors inserted since that
+    /// checkpoint to its storage.
+    pub fn resume(&self, hnsw: &mut HNSW<f32, InMemoryVectorStorage<Float32Type>>) {
+        for id in hnsw.nodes.len() as u32..hnsw.vectors.len() as u32 {
+            self.insert(hnsw, id);
+        }
+    }
+
+    /// Insert the `id`-th vector of `hnsw.vectors` into the graph.
+    fn insert(&self, hnsw: &mut HNSW<f32, InMemoryVectorStorage<Float32Type>>, id: u32) {
+        let level = self.random_level();
+        let query = hnsw.vectors.get(id).expect("vector not found").to_vec();
+        let ctx = hnsw.vectors.prepare_query(&query);
+
+        let Some(mut entry_point) = hnsw.entry_point else {
+            hnsw.nodes.push(GraphNode::new(id, level));
+            hnsw.dirty.insert(id);
+            hnsw.entry_point = Some(id);
+            hnsw.max_level = level;
+            return;
+        };
+        let top_level = hnsw.max_level;
+
+        // Greedily descend from the top layer down to `level + 1`, keeping
+        // only the single closest node found as the entry point for the next
+        // layer down.
+        for layer in (level + 1..=top_level).rev() {
+            let ep_node = hnsw.node(entry_point).expect("entry point not found");
+            let nearest = hnsw.search_layer(&ctx, ep_node, 1, layer, None);
+            if let Some(closest) = nearest.into_iter().next() {
+                entry_point = closest.node.id;
+            }
+        }
+
+        hnsw.nodes.push(GraphNode::new(id, level));
+        hnsw.dirty.insert(id);
+
+        // From the node's own layer down to layer 0, find `ef_construction`
+        // candidates, keep the `m_max` closest as neighbors, and make the
+        // edges bidirectional, pruning the other endpoint if it overflows.
+        for layer in (0..=level.min(top_level)).rev() {
+            let ep_node = hnsw.node(entry_point).expect("entry point not found");
+            let candidates = hnsw.search_layer(&ctx, ep_node, self.ef_construction, layer, None);
+
+            let neighbors: Vec<u32> = candidates
+                .iter()
+                .take(self.m_max)
+                .map(|c| c.node.id)
+                .collect();
+            for neighbor_id in neighbors {
+                hnsw.connect(id, neighbor_id, layer);
+                hnsw.connect(neighbor_id, id, layer);
+                hnsw.prune_neighbors(neighbor_id, layer, self.m_max);
+            }
+
+            if let Some(closest) = candidates.into_iter().next() {
+                entry_point = closest.node.id;
+            }
+        }
+
+        if level > top_level {
+            hnsw.max_level = level;
+            hnsw.entry_point = Some(id);
+        }
+    }
+
+    /// Assign a random level to a new node, using the exponential decay
+    /// distribution standard to HNSW: draw `u` uniformly in `(0, 1]` and set
+    /// `level = floor(-ln(u) * m_L)`, where `m_L = 1 / ln(m_max)`.
     fn random_level(&self) -> u16 {
-        unimplemented!()
+        let m_l = 1.0 / (self.m_max as f64).ln();
+        let u: f64 = rand::thread_rng().gen_range(f64::MIN_POSITIVE..=1.0);
+        let level = (-u.ln() * m_l).floor() as u16;
+        level.min(self.max_level)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::vector::hnsw::builder::HNSWBUilder;
+    use crate::vector::hnsw::test_utils::{default_test_hnsw, DEFAULT_NUM_ROWS};
 
     #[test]
     fn test_hnsw_builder() {
-        let builder = HNSWBUilder::new().max_level(8);
+        let hnsw = default_test_hnsw();
 
-        unimplemented!()
+        assert_eq!(hnsw.nodes.len(), DEFAULT_NUM_ROWS);
+        assert!(hnsw.entry_point.is_some());
+        for node in &hnsw.nodes {
+            // At least a layer-0 neighbor list must be present.
+            assert!(!node.neighbors.is_empty());
+        }
     }
 }
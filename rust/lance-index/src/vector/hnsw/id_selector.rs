@@ -0,0 +1,91 @@
+// Copyright 2024 Lance Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! ID selectors, used to restrict a graph search to a subset of ids.
+//!
+//! Modeled after FAISS' `IDSelector`: a selector is consulted when a
+//! candidate would otherwise be returned from a search, so that e.g.
+//! deleted rows or rows that don't match a scalar predicate can be excluded
+//! without losing the graph connectivity they provide.
+
+use std::ops::Range;
+
+use roaring::RoaringBitmap;
+
+/// A predicate over vector ids.
+pub trait IdSelector {
+    /// Returns `true` if `id` is a member of the selected set.
+    fn is_member(&self, id: u32) -> bool;
+}
+
+/// Select the ids set in a [`RoaringBitmap`], e.g. the set of live (i.e.
+/// not-yet-deleted) row ids.
+pub struct BitmapSelector<'a> {
+    bitmap: &'a RoaringBitmap,
+}
+
+impl<'a> BitmapSelector<'a> {
+    pub fn new(bitmap: &'a RoaringBitmap) -> Self {
+        Self { bitmap }
+    }
+}
+
+impl IdSelector for BitmapSelector<'_> {
+    fn is_member(&self, id: u32) -> bool {
+        self.bitmap.contains(id)
+    }
+}
+
+/// Select ids within a contiguous `[start, end)` range.
+pub struct RangeSelector {
+    range: Range<u32>,
+}
+
+impl RangeSelector {
+    pub fn new(range: Range<u32>) -> Self {
+        Self { range }
+    }
+}
+
+impl IdSelector for RangeSelector {
+    fn is_member(&self, id: u32) -> bool {
+        self.range.contains(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitmap_selector() {
+        let mut bitmap = RoaringBitmap::new();
+        bitmap.insert(1);
+        bitmap.insert(3);
+
+        let selector = BitmapSelector::new(&bitmap);
+        assert!(selector.is_member(1));
+        assert!(selector.is_member(3));
+        assert!(!selector.is_member(2));
+    }
+
+    #[test]
+    fn test_range_selector() {
+        let selector = RangeSelector::new(10..20);
+        assert!(!selector.is_member(9));
+        assert!(selector.is_member(10));
+        assert!(selector.is_member(19));
+        assert!(!selector.is_member(20));
+    }
+}
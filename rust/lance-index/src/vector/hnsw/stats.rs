@@ -0,0 +1,149 @@
+// Copyright 2024 Lance Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Graph introspection, for tuning `m_max`/`ef_construction`/`random_level`
+//! and debugging a badly connected graph.
+
+use std::fmt;
+
+use num_traits::Float;
+
+use super::super::graph::VectorStorage;
+use super::HNSW;
+
+/// Per-layer connectivity stats, as reported by [`HNSW::stats`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerStats {
+    pub layer: u16,
+    pub num_nodes: usize,
+    pub num_edges: usize,
+    pub avg_out_degree: f32,
+    pub min_out_degree: usize,
+    pub max_out_degree: usize,
+}
+
+/// A snapshot of an [`HNSW`] graph's shape, for tuning and debugging.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HnswStats {
+    /// The layer the entry point sits on, i.e. the graph's `max_level`.
+    pub entry_point_level: u16,
+
+    /// Number of nodes with no layer-0 neighbors -- unreachable from a
+    /// layer-0 search that doesn't happen to start there.
+    pub num_orphaned_nodes: usize,
+
+    /// Stats for each layer, ordered from layer 0 to `entry_point_level`.
+    pub layers: Vec<LayerStats>,
+}
+
+impl fmt::Display for HnswStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "HNSW graph: entry point at layer {}, {} orphaned node(s) at layer 0",
+            self.entry_point_level, self.num_orphaned_nodes
+        )?;
+        for layer in &self.layers {
+            writeln!(
+                f,
+                "  layer {}: {} nodes, {} edges, out-degree avg={:.2} min={} max={}",
+                layer.layer,
+                layer.num_nodes,
+                layer.num_edges,
+                layer.avg_out_degree,
+                layer.min_out_degree,
+                layer.max_out_degree
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: Float, S: VectorStorage<T>> HNSW<T, S> {
+    /// Walk the graph and report per-layer node/edge counts and out-degree
+    /// distribution, plus the entry point's level and the number of
+    /// layer-0 orphaned nodes.
+    ///
+    /// Useful to sanity-check that `m_max`, `ef_construction`, and
+    /// `random_level` produced a well-connected graph rather than a
+    /// degenerate one.
+    pub fn stats(&self) -> HnswStats {
+        let layers = (0..=self.max_level)
+            .map(|layer| {
+                let out_degrees: Vec<usize> = self
+                    .nodes
+                    .iter()
+                    .filter_map(|node| node.neighbors.get(layer as usize))
+                    .map(|neighbors| neighbors.len())
+                    .collect();
+
+                let num_nodes = out_degrees.len();
+                let num_edges: usize = out_degrees.iter().sum();
+                let avg_out_degree = if num_nodes > 0 {
+                    num_edges as f32 / num_nodes as f32
+                } else {
+                    0.0
+                };
+
+                LayerStats {
+                    layer,
+                    num_nodes,
+                    num_edges,
+                    avg_out_degree,
+                    min_out_degree: out_degrees.iter().copied().min().unwrap_or(0),
+                    max_out_degree: out_degrees.iter().copied().max().unwrap_or(0),
+                }
+            })
+            .collect();
+
+        let num_orphaned_nodes = self
+            .nodes
+            .iter()
+            .filter(|node| node.neighbors.first().map_or(true, |l0| l0.is_empty()))
+            .count();
+
+        HnswStats {
+            entry_point_level: self.max_level,
+            num_orphaned_nodes,
+            layers,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vector::hnsw::test_utils::{default_test_hnsw, DEFAULT_NUM_ROWS};
+
+    #[test]
+    fn test_stats() {
+        let hnsw = default_test_hnsw();
+
+        let stats = hnsw.stats();
+
+        assert_eq!(stats.entry_point_level, hnsw.max_level);
+        assert_eq!(stats.layers.len(), hnsw.max_level as usize + 1);
+        // Layer 0 holds every node.
+        assert_eq!(stats.layers[0].num_nodes, DEFAULT_NUM_ROWS);
+        // Higher layers hold a shrinking subset of the nodes.
+        for pair in stats.layers.windows(2) {
+            assert!(pair[0].num_nodes >= pair[1].num_nodes);
+        }
+
+        // The Display impl should at least mention every layer.
+        let rendered = stats.to_string();
+        for layer in &stats.layers {
+            assert!(rendered.contains(&format!("layer {}", layer.layer)));
+        }
+    }
+}
@@ -0,0 +1,54 @@
+// Copyright 2024 Lance Developers.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Test fixtures shared by the `hnsw` module's unit tests, so every file
+//! doesn't have to re-derive the same `l2` distance function and
+//! `InMemoryVectorStorage` setup.
+
+use std::sync::Arc;
+
+use arrow_array::types::Float32Type;
+use arrow_array::Float32Array;
+use lance_linalg::MatrixView;
+
+use super::super::graph::InMemoryVectorStorage;
+use super::builder::HNSWBUilder;
+use super::HNSW;
+
+/// Squared Euclidean distance, the metric used by every test fixture below.
+pub(crate) fn l2(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y).powi(2)).sum()
+}
+
+/// `num_rows` row-major vectors of `dim` values each, valued
+/// `0..(dim * num_rows)`, indexed under [`l2`].
+pub(crate) fn test_vectors(dim: usize, num_rows: usize) -> InMemoryVectorStorage<Float32Type> {
+    let values = Float32Array::from_iter_values((0..(dim * num_rows)).map(|v| v as f32));
+    let matrix = MatrixView::<Float32Type>::new(Arc::new(values), dim);
+    InMemoryVectorStorage::new(matrix, l2)
+}
+
+/// The 8-dimensional, 200-row fixture shared by most graph-level tests.
+pub(crate) const DEFAULT_DIM: usize = 8;
+pub(crate) const DEFAULT_NUM_ROWS: usize = 200;
+
+/// A graph built over [`DEFAULT_DIM`]/[`DEFAULT_NUM_ROWS`] test vectors, with
+/// the `max_level`/`max_num_edges`/`ef_construction` most tests exercise.
+pub(crate) fn default_test_hnsw() -> HNSW<f32, InMemoryVectorStorage<Float32Type>> {
+    HNSWBUilder::new()
+        .max_level(8)
+        .max_num_edges(16)
+        .ef_construction(50)
+        .build(test_vectors(DEFAULT_DIM, DEFAULT_NUM_ROWS))
+}